@@ -1,10 +1,14 @@
-use std::{fs, process, io};
+use std::{fs, io};
 use libc;
+use mio;
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+use std::process;
 use libc::c_ulong;
+use std::net::Ipv4Addr;
 use std::os::unix::io::{RawFd, AsRawFd};
-use std::io::{Write, Read};
+use std::io::{Write, Read, IoSlice, IoSliceMut};
 
-const MTU: &'static str = "1380";
+const DEFAULT_MTU: u32 = 1380;
 
 #[cfg(target_os = "linux")]
 use libc::c_short;
@@ -18,9 +22,25 @@ const IFF_TUN: c_short = 0x0001;
 const IFF_NO_PI: c_short = 0x1000;
 #[cfg(target_os = "linux")]
 const TUNSETIFF: c_ulong = 0x400454ca; // TODO: use _IOW('T', 202, int)
+#[cfg(target_os = "linux")]
+use libc::c_int;
+#[cfg(target_os = "linux")]
+use std::mem;
+#[cfg(target_os = "linux")]
+const SIOCSIFADDR: c_ulong = 0x8916;
+#[cfg(target_os = "linux")]
+const SIOCSIFNETMASK: c_ulong = 0x891c;
+#[cfg(target_os = "linux")]
+const SIOCGIFFLAGS: c_ulong = 0x8913;
+#[cfg(target_os = "linux")]
+const SIOCSIFFLAGS: c_ulong = 0x8914;
+#[cfg(target_os = "linux")]
+const SIOCSIFMTU: c_ulong = 0x8922;
+#[cfg(target_os = "linux")]
+const IFF_UP: c_short = 0x0001;
+#[cfg(target_os = "linux")]
+const IFF_RUNNING: c_short = 0x0040;
 
-#[cfg(target_os = "macos")]
-use nix;
 #[cfg(target_os = "macos")]
 use nix::fcntl::*;
 #[cfg(target_os = "macos")]
@@ -42,11 +62,75 @@ const CTLIOCGINFO: c_ulong = 0xc0644e03; // TODO: use _IOWR('N', 3, struct ctl_i
 #[cfg(target_os = "macos")]
 const UTUN_CONTROL_NAME: &'static str = "com.apple.net.utun_control";
 
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+use libc::c_int;
+#[cfg(target_os = "freebsd")]
+const TUNSIFHEAD: c_ulong = 0x80047460; // _IOW('t', 96, int)
+
+/// Number of bytes in the leading big-endian address-family header that
+/// macOS utun and the BSD tun devices carry in front of every packet.
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+const AF_HEADER_LEN: usize = 4;
+
+/// Builds the 4-byte address-family header for an outgoing packet: `AF_INET6`
+/// (10) for IPv6, `AF_INET` (2) otherwise. Shared by every platform whose tun
+/// device carries the header so the framing stays identical across them.
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+fn af_header(buf: &[u8]) -> [u8; AF_HEADER_LEN] {
+    let ip_v = buf.first().map_or(0, |b| b >> 4);
+    if ip_v == 6 {
+        [0, 0, 0, 10]
+    } else {
+        [0, 0, 0, 2]
+    }
+}
+
+// The kernel's `struct ifreq` is `ifr_name[IFNAMSIZ]` plus a 16-byte union, and
+// the socket ioctls `copy_from_user`/`copy_to_user` the full `sizeof(ifreq)`
+// regardless of which union member we care about. Each of these views must
+// therefore be at least as large as a real `ifreq`, so we pad the tail out to
+// the union size; otherwise a GET ioctl (e.g. `SIOCGIFFLAGS`) scribbles past
+// the struct and corrupts the stack.
+#[cfg(target_os = "linux")]
+const IFR_UNION_SIZE: usize = 16;
+
 #[cfg(target_os = "linux")]
 #[repr(C)]
 pub struct ioctl_flags_data {
     pub ifr_name: [u8; IFNAMSIZ],
     pub ifr_flags: c_short,
+    pad: [u8; IFR_UNION_SIZE - 2],
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct ifreq_addr {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_addr: libc::sockaddr,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct ifreq_mtu {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_mtu: c_int,
+    pad: [u8; IFR_UNION_SIZE - 4],
+}
+
+#[cfg(target_os = "linux")]
+fn if_name_buf(name: &str) -> [u8; IFNAMSIZ] {
+    let mut buffer = [0u8; IFNAMSIZ];
+    let bytes = name.as_bytes();
+    buffer[..bytes.len()].clone_from_slice(bytes);
+    buffer
+}
+
+#[cfg(target_os = "linux")]
+fn sockaddr_in(addr: Ipv4Addr) -> libc::sockaddr {
+    let mut sin: libc::sockaddr_in = unsafe { mem::zeroed() };
+    sin.sin_family = libc::AF_INET as libc::sa_family_t;
+    sin.sin_addr = libc::in_addr { s_addr: u32::from(addr).to_be() };
+    unsafe { mem::transmute::<libc::sockaddr_in, libc::sockaddr>(sin) }
 }
 
 #[cfg(target_os = "macos")]
@@ -67,6 +151,67 @@ pub struct sockaddr_ctl {
     pub sc_reserved: [u32; 5],
 }
 
+/// Everything that can go wrong setting up a `Tun`. The `io::Error` carried by
+/// `Open`/`Ioctl` keeps the raw `errno` (via `raw_os_error`) so a daemon can
+/// tell `EPERM` (needs root) from `EBUSY` (unit already taken) and move on to
+/// the next `utun`/`tunN` unit.
+#[derive(Debug)]
+pub enum TunError {
+    /// Opening the tun device node (or the macOS control socket, or the Linux
+    /// socket used by `up` for configuration) failed.
+    Open(io::Error),
+    /// An `ioctl`/`connect`/`fcntl`/`ifconfig` call failed.
+    Ioctl(io::Error),
+    /// The requested interface name did not fit in `IFNAMSIZ`.
+    NameTooLong,
+    /// The current target platform has no tun implementation.
+    Unsupported,
+}
+
+impl TunError {
+    /// The underlying OS error number, when the failure came from a syscall.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match *self {
+            TunError::Open(ref e) | TunError::Ioctl(ref e) => e.raw_os_error(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            TunError::Open(ref e) => write!(f, "couldn't open tun device: {}", e),
+            TunError::Ioctl(ref e) => write!(f, "tun ioctl failed: {}", e),
+            TunError::NameTooLong => write!(f, "interface name exceeds IFNAMSIZ"),
+            TunError::Unsupported => write!(f, "tun is unsupported on this platform"),
+        }
+    }
+}
+
+impl std::error::Error for TunError {}
+
+/// Addressing applied to the interface when it is brought `up`. Callers that
+/// want a subnet other than the historical `10.10.10.0/24` build this
+/// directly; `TunConfig::with_id` reproduces the old default.
+#[derive(Clone)]
+pub struct TunConfig {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub mtu: u32,
+}
+
+impl TunConfig {
+    /// The historical kytan default: `10.10.10.<self_id>/24` at the crate MTU.
+    pub fn with_id(self_id: u8) -> TunConfig {
+        TunConfig {
+            address: Ipv4Addr::new(10, 10, 10, self_id),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            mtu: DEFAULT_MTU,
+        }
+    }
+}
+
 pub struct Tun {
     handle: fs::File,
     if_name: String,
@@ -78,50 +223,100 @@ impl AsRawFd for Tun {
     }
 }
 
+impl mio::event::Source for Tun {
+    fn register(&mut self,
+                registry: &mio::Registry,
+                token: mio::Token,
+                interests: mio::Interest)
+                -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self,
+                  registry: &mio::Registry,
+                  token: mio::Token,
+                  interests: mio::Interest)
+                  -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
 impl Tun {
-    pub fn create(name: u8) -> Tun {
-        let (handle, if_name) = Tun::create_if(name);
-        Tun {
+    pub fn create(name: u8) -> Result<Tun, TunError> {
+        let (handle, if_name) = Tun::create_if(name)?;
+        Ok(Tun {
             handle: handle,
             if_name: if_name,
+        })
+    }
+
+    /// Flips the `O_NONBLOCK` flag on the underlying file descriptor. When
+    /// non-blocking mode is enabled, reads that would otherwise block return
+    /// `io::ErrorKind::WouldBlock`, which is what lets `Tun` be driven from a
+    /// single-threaded `mio::Poll` loop alongside the UDP tunnel socket.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let new_flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        let res = unsafe { libc::fcntl(fd, libc::F_SETFL, new_flags) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
         }
+        Ok(())
     }
 
     #[cfg(target_os = "linux")]
-    fn create_if(name: u8) -> (fs::File, String) {
+    fn create_if(name: u8) -> Result<(fs::File, String), TunError> {
         let path = path::Path::new("/dev/net/tun");
-        let file = match fs::OpenOptions::new().read(true).write(true).open(&path) {
-            Err(why) => panic!("Couldn't open device '{}': {:?}", path.display(), why),
-            Ok(file) => file,
-        };
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(TunError::Open)?;
+
+        let full_name = format!("tun{}", name);
+        if full_name.len() >= IFNAMSIZ {
+            return Err(TunError::NameTooLong);
+        }
 
         let mut req = ioctl_flags_data {
             ifr_name: {
                 let mut buffer = [0u8; IFNAMSIZ];
-                let full_name = format!("tun{}", name);
                 buffer[..full_name.len()].clone_from_slice(full_name.as_bytes());
                 buffer
             },
             ifr_flags: IFF_TUN | IFF_NO_PI,
+            pad: [0; IFR_UNION_SIZE - 2],
         };
 
         let res = unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF, &mut req) }; // TUNSETIFF
         if res < 0 {
-            panic!("{}", io::Error::last_os_error());
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
         }
 
         let size = req.ifr_name.iter().position(|&r| r == 0).unwrap();
 
         let if_name = String::from_utf8(req.ifr_name[..size].to_vec()).unwrap();
-        (file, if_name)
+        Ok((file, if_name))
     }
 
     #[cfg(target_os = "macos")]
-    fn create_if(name: u8) -> (fs::File, String) {
+    fn create_if(name: u8) -> Result<(fs::File, String), TunError> {
         let handle = {
             let fd = unsafe { libc::socket(PF_SYSTEM, libc::SOCK_DGRAM, SYSPROTO_CONTROL) };
             if fd < 0 {
-                panic!("{}", io::Error::last_os_error());
+                return Err(TunError::Open(io::Error::last_os_error()));
             }
             unsafe { fs::File::from_raw_fd(fd) }
         };
@@ -137,8 +332,10 @@ impl Tun {
 
         let res = unsafe { libc::ioctl(handle.as_raw_fd(), CTLIOCGINFO, &mut info) };
         if res != 0 {
-            nix::unistd::close(handle.as_raw_fd()).unwrap();
-            panic!("{}", io::Error::last_os_error());
+            // `handle` owns the fd and closes it on drop; don't close it here
+            // too, or a later call could reuse the number and we'd close an
+            // unrelated descriptor.
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
         }
 
         let addr = sockaddr_ctl {
@@ -159,57 +356,218 @@ impl Tun {
                           mem::size_of_val(&addr) as socklen_t)
         };
         if res != 0 {
-            panic!("{}", io::Error::last_os_error());
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
         }
 
-        fcntl(handle.as_raw_fd(), FcntlArg::F_SETFL(O_NONBLOCK)).unwrap();
-        fcntl(handle.as_raw_fd(), FcntlArg::F_SETFD(FD_CLOEXEC)).unwrap();
+        fcntl(handle.as_raw_fd(), FcntlArg::F_SETFL(O_NONBLOCK))
+            .map_err(|e| TunError::Ioctl(io::Error::from_raw_os_error(e as i32)))?;
+        fcntl(handle.as_raw_fd(), FcntlArg::F_SETFD(FD_CLOEXEC))
+            .map_err(|e| TunError::Ioctl(io::Error::from_raw_os_error(e as i32)))?;
 
         let if_name = format!("utun{}", name);
-        (handle, if_name)
+        Ok((handle, if_name))
     }
 
-    pub fn up(&self, self_id: u8) {
-        let mut status = if cfg!(target_os = "linux") {
-            process::Command::new("ifconfig")
-                .arg(self.if_name.clone())
-                .arg(format!("10.10.10.{}/24", self_id))
-                .status()
-                .unwrap()
-        } else if cfg!(target_os = "macos") {
-            process::Command::new("ifconfig")
-                .arg(self.if_name.clone())
-                .arg(format!("10.10.10.{}", self_id))
-                .arg("10.10.10.1")
-                .status()
-                .unwrap()
-        } else {
-            unimplemented!()
+    #[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+    fn create_if(name: u8) -> Result<(fs::File, String), TunError> {
+        let if_name = format!("tun{}", name);
+        let path = format!("/dev/{}", if_name);
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(TunError::Open)?;
+
+        // FreeBSD tun devices default to "link-layer" mode with no header; turn
+        // on the 4-byte address-family prefix so the framing matches the macOS
+        // utun convention. OpenBSD tun devices already carry the header.
+        #[cfg(target_os = "freebsd")]
+        {
+            let on: c_int = 1;
+            let res = unsafe { libc::ioctl(file.as_raw_fd(), TUNSIFHEAD, &on) };
+            if res < 0 {
+                return Err(TunError::Ioctl(io::Error::last_os_error()));
+            }
+        }
+
+        Ok((file, if_name))
+    }
+
+    /// Assigns the addressing from `config` and brings the interface up. On
+    /// Linux this issues `ioctl`s directly on a temporary socket and applies
+    /// the address, netmask and MTU. On macOS/BSD it still drives `ifconfig`
+    /// with the address and MTU only: `config.netmask` is not applied there and
+    /// the point-to-point peer is always `10.10.10.1`.
+    #[cfg(target_os = "linux")]
+    pub fn up(&self, config: &TunConfig) -> Result<(), TunError> {
+        let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+        if sock < 0 {
+            return Err(TunError::Open(io::Error::last_os_error()));
+        }
+        let result = self.configure(sock, config);
+        unsafe { libc::close(sock) };
+        result
+    }
+
+    #[cfg(target_os = "linux")]
+    fn configure(&self, sock: c_int, config: &TunConfig) -> Result<(), TunError> {
+        self.set_sockaddr(sock, SIOCSIFADDR, config.address)?;
+        self.set_sockaddr(sock, SIOCSIFNETMASK, config.netmask)?;
+
+        // Read the current flags, then OR in UP|RUNNING and write them back.
+        let mut flags = ioctl_flags_data {
+            ifr_name: if_name_buf(&self.if_name),
+            ifr_flags: 0,
+            pad: [0; IFR_UNION_SIZE - 2],
+        };
+        if unsafe { libc::ioctl(sock, SIOCGIFFLAGS, &mut flags) } < 0 {
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
+        }
+        flags.ifr_flags |= IFF_UP | IFF_RUNNING;
+        if unsafe { libc::ioctl(sock, SIOCSIFFLAGS, &mut flags) } < 0 {
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
+        }
+
+        let mut mtu = ifreq_mtu {
+            ifr_name: if_name_buf(&self.if_name),
+            ifr_mtu: config.mtu as c_int,
+            pad: [0; IFR_UNION_SIZE - 4],
         };
+        if unsafe { libc::ioctl(sock, SIOCSIFMTU, &mut mtu) } < 0 {
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
 
-        assert!(status.success());
-
-        status = if cfg!(target_os = "linux") {
-            process::Command::new("ifconfig")
-                .arg(self.if_name.clone())
-                .arg("mtu")
-                .arg(MTU)
-                .arg("up")
-                .status()
-                .unwrap()
-        } else if cfg!(target_os = "macos") {
-            process::Command::new("ifconfig")
-                .arg(self.if_name.clone())
-                .arg("mtu")
-                .arg(MTU)
-                .arg("up")
-                .status()
-                .unwrap()
-        } else {
-            unimplemented!()
+    #[cfg(target_os = "linux")]
+    fn set_sockaddr(&self, sock: c_int, request: c_ulong, addr: Ipv4Addr) -> Result<(), TunError> {
+        let mut req = ifreq_addr {
+            ifr_name: if_name_buf(&self.if_name),
+            ifr_addr: sockaddr_in(addr),
         };
+        if unsafe { libc::ioctl(sock, request, &mut req) } < 0 {
+            return Err(TunError::Ioctl(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+    pub fn up(&self, config: &TunConfig) -> Result<(), TunError> {
+        let status = process::Command::new("ifconfig")
+            .arg(self.if_name.clone())
+            .arg(format!("{}", config.address))
+            .arg("10.10.10.1")
+            .status()
+            .map_err(TunError::Ioctl)?;
+        if !status.success() {
+            return Err(TunError::Ioctl(io::Error::new(io::ErrorKind::Other,
+                                                      "ifconfig failed to set the address")));
+        }
 
-        assert!(status.success());
+        let status = process::Command::new("ifconfig")
+            .arg(self.if_name.clone())
+            .arg("mtu")
+            .arg(format!("{}", config.mtu))
+            .arg("up")
+            .status()
+            .map_err(TunError::Ioctl)?;
+        if !status.success() {
+            return Err(TunError::Ioctl(io::Error::new(io::ErrorKind::Other,
+                                                      "ifconfig failed to set the mtu")));
+        }
+        Ok(())
+    }
+
+    /// Reads a single packet from the device in one `readv`, scattering it
+    /// across `bufs`. A tun fd delivers exactly one packet per syscall, so this
+    /// is not a batch read: the packet is spread over the buffers in order and
+    /// the returned count is its total length. Use several calls in a poll loop
+    /// to drain the fd, one packet each.
+    #[cfg(target_os = "linux")]
+    pub fn read_packets(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let res = unsafe {
+            libc::readv(self.as_raw_fd(),
+                        bufs.as_ptr() as *const libc::iovec,
+                        bufs.len() as c_int)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(res as usize)
+    }
+
+    /// Writes a single packet to the device in one `writev`, gathering its
+    /// fragments from `bufs`. A tun fd accepts exactly one packet per syscall,
+    /// so all of `bufs` is concatenated into one datagram; it is the caller's
+    /// job to pass the fragments of a single packet, not several packets.
+    /// Returns the number of bytes written.
+    #[cfg(target_os = "linux")]
+    pub fn write_packets(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let res = unsafe {
+            libc::writev(self.as_raw_fd(),
+                         bufs.as_ptr() as *const libc::iovec,
+                         bufs.len() as c_int)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(res as usize)
+    }
+
+    /// Reads a single packet, scattering it across `bufs`. The device leads
+    /// every packet with a 4-byte address-family header, so one scratch iovec
+    /// is placed ahead of the caller buffers to absorb it; the returned count
+    /// is the payload length and excludes the header. Like the Linux variant
+    /// this is a single-packet scatter, not a batch read.
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+    pub fn read_packets(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+        let mut header = [0u8; AF_HEADER_LEN];
+        let mut iovecs: Vec<libc::iovec> = Vec::with_capacity(bufs.len() + 1);
+        iovecs.push(libc::iovec {
+            iov_base: header.as_mut_ptr() as *mut libc::c_void,
+            iov_len: AF_HEADER_LEN,
+        });
+        for buf in bufs.iter_mut() {
+            iovecs.push(libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            });
+        }
+        let res = unsafe {
+            libc::readv(self.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as c_int)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((res as usize).saturating_sub(AF_HEADER_LEN))
+    }
+
+    /// Writes a single packet, gathering its fragments from `bufs`. A 4-byte
+    /// address-family header iovec is prepended once so the device sees the
+    /// framing it expects; the returned count is the payload length and
+    /// excludes the header. Like the Linux variant this gathers the fragments
+    /// of a single packet, not several packets.
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+    pub fn write_packets(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let header = bufs.first().map(|b| af_header(b)).unwrap_or([0u8; AF_HEADER_LEN]);
+        let mut iovecs: Vec<libc::iovec> = Vec::with_capacity(bufs.len() + 1);
+        iovecs.push(libc::iovec {
+            iov_base: header.as_ptr() as *mut libc::c_void,
+            iov_len: AF_HEADER_LEN,
+        });
+        for buf in bufs.iter() {
+            iovecs.push(libc::iovec {
+                iov_base: buf.as_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            });
+        }
+        let res = unsafe {
+            libc::writev(self.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as c_int)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((res as usize).saturating_sub(AF_HEADER_LEN))
     }
 }
 
@@ -221,14 +579,19 @@ impl Read for Tun {
 
 
 
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut data = [0u8; 1600];
         let result = self.handle.read(&mut data);
         match result {
             Ok(len) => {
-                buf[..len - 4].clone_from_slice(&data[4..len]);
-                Ok(if len > 4 { len - 4 } else { 0 })
+                if len > AF_HEADER_LEN {
+                    let payload = len - AF_HEADER_LEN;
+                    buf[..payload].clone_from_slice(&data[AF_HEADER_LEN..len]);
+                    Ok(payload)
+                } else {
+                    Ok(0)
+                }
             }
             Err(e) => Err(e),
         }
@@ -241,17 +604,12 @@ impl Write for Tun {
         self.handle.write(buf)
     }
 
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let ip_v = buf[0] & 0xf;
-        let mut data: Vec<u8> = if ip_v == 6 {
-            vec![0, 0, 0, 10]
-        } else {
-            vec![0, 0, 0, 2]
-        };
+        let mut data: Vec<u8> = af_header(buf).to_vec();
         data.write_all(buf).unwrap();
         match self.handle.write(&data) {
-            Ok(len) => Ok(if len > 4 { len - 4 } else { 0 }),
+            Ok(len) => Ok(if len > AF_HEADER_LEN { len - AF_HEADER_LEN } else { 0 }),
             Err(e) => Err(e),
         }
     }